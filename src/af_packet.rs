@@ -0,0 +1,110 @@
+use super::packet;
+use super::engine;
+use super::device::{Device, SpscQueue, ChannelDevice, DeviceApp};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+// AF_PACKET DEVICE APP
+//
+// Moves packets between the engine and a host network interface via a
+// PF_PACKET/SOCK_RAW socket bound to the interface, following the same
+// channel-based split as tap.rs: blocking recv/send happen on background
+// threads, decoupled from the engine breathe loop via device::SpscQueue.
+//
+//   AfPacket - AppConfig: {ifname, mtu} binds a raw socket to the interface
+
+fn if_index(ifname: &str) -> libc::c_uint {
+    let cname = std::ffi::CString::new(ifname).expect("invalid interface name");
+    let index = unsafe { libc::if_nametoindex(cname.as_ptr()) };
+    assert!(index != 0, "unknown interface {}", ifname);
+    index
+}
+
+fn open_raw_socket(ifname: &str) -> RawFd {
+    let fd = unsafe {
+        libc::socket(libc::AF_PACKET, libc::SOCK_RAW, (libc::ETH_P_ALL as u16).to_be() as i32)
+    };
+    assert!(fd >= 0, "failed to open AF_PACKET socket (need CAP_NET_RAW)");
+
+    let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+    addr.sll_family = libc::AF_PACKET as u16;
+    addr.sll_protocol = (libc::ETH_P_ALL as u16).to_be();
+    addr.sll_ifindex = if_index(ifname) as i32;
+
+    let res = unsafe {
+        libc::bind(fd,
+                   &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                   mem::size_of::<libc::sockaddr_ll>() as u32)
+    };
+    assert!(res == 0, "failed to bind AF_PACKET socket to {}", ifname);
+
+    fd
+}
+
+fn rx_thread(fd: RawFd, rx: Arc<SpscQueue<Box<packet::Packet>>>, link_up: Arc<AtomicBool>) {
+    link_up.store(true, Ordering::Relaxed);
+    loop {
+        let mut p = packet::allocate();
+        let n = unsafe {
+            libc::recv(fd, p.data.as_mut_ptr() as *mut libc::c_void,
+                       p.data.len(), 0)
+        };
+        if n > 0 {
+            p.length = n as u16;
+            if let Err(p) = rx.try_push(p) {
+                // Engine isn't keeping up; drop the frame.
+                packet::free(p);
+            }
+        } else {
+            packet::free(p);
+        }
+    }
+}
+
+fn tx_thread(fd: RawFd, tx: Arc<SpscQueue<Box<packet::Packet>>>) {
+    loop {
+        let mut p = tx.pop_blocking();
+        unsafe {
+            libc::send(fd, p.data.as_ptr() as *const libc::c_void,
+                       p.length as usize, 0);
+        }
+        p.length = 0;
+        packet::free(p);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AfPacket { pub ifname: String, pub mtu: usize }
+
+impl engine::AppConfig for AfPacket {
+    fn new(&self) -> Box<dyn engine::App> {
+        let rx_fd = open_raw_socket(&self.ifname);
+        let tx_fd = unsafe { libc::dup(rx_fd) };
+        assert!(tx_fd >= 0, "failed to dup AF_PACKET socket");
+
+        let rx: Arc<SpscQueue<Box<packet::Packet>>> = SpscQueue::new(1024);
+        let tx: Arc<SpscQueue<Box<packet::Packet>>> = SpscQueue::new(1024);
+        let link_up = Arc::new(AtomicBool::new(false));
+
+        let rx_queue = rx.clone();
+        let rx_link_up = link_up.clone();
+        thread::Builder::new()
+            .name(format!("af_packet-rx:{}", self.ifname))
+            .spawn(move || rx_thread(rx_fd, rx_queue, rx_link_up))
+            .expect("failed to spawn AF_PACKET RX thread");
+
+        let tx_queue = tx.clone();
+        thread::Builder::new()
+            .name(format!("af_packet-tx:{}", self.ifname))
+            .spawn(move || tx_thread(tx_fd, tx_queue))
+            .expect("failed to spawn AF_PACKET TX thread");
+
+        let device: Box<dyn Device> =
+            Box::new(ChannelDevice::new(rx, tx, self.mtu, link_up));
+        Box::new(DeviceApp::new(device))
+    }
+}
@@ -0,0 +1,29 @@
+use super::engine;
+
+// AF_XDP DEVICE APP
+//
+// Placeholder AppConfig for an AF_XDP-backed Device, following the same
+// interface as tap.rs/af_packet.rs so a config can name "af_xdp" without the
+// engine caring which host-I/O backend is behind it.
+//
+// XXX - Not yet implemented: driving AF_XDP requires setting up a UMEM
+// (mmap'd packet buffer ring shared with the kernel), the FILL/COMPLETION/
+// RX/TX rings via setsockopt(XDP_*), and loading/attaching an XDP program
+// that redirects frames into the socket (bpf(BPF_PROG_LOAD) + XDP_REDIRECT).
+// None of that plumbing exists in this crate yet, so construction panics
+// rather than silently falling back to a different backend; engine::
+// start_app catches that panic and reports it as a ConfigError::AppInit,
+// the same way any other bad config entry is rejected.
+//
+//   AfXdp - AppConfig: {ifname, queue_id, mtu}
+
+#[derive(Clone, Debug)]
+pub struct AfXdp { pub ifname: String, pub queue_id: u32, pub mtu: usize }
+
+impl engine::AppConfig for AfXdp {
+    fn new(&self) -> Box<dyn engine::App> {
+        panic!("AF_XDP backend (interface {}, queue {}) is not yet implemented: \
+                needs UMEM + ring setup and an attached XDP_REDIRECT program",
+               self.ifname, self.queue_id);
+    }
+}
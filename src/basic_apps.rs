@@ -17,12 +17,11 @@ impl engine::App for SourceApp {
     fn has_pull(&self) -> bool { true }
     fn pull(&self, app: &engine::AppState) {
         for output in app.output.values() {
-            let mut output = output.borrow_mut();
             for _ in 0..engine::PULL_NPACKETS {
                 let mut p = packet::allocate();
                 lib::fill(&mut p.data, self.size as usize, 0);
                 p.length = self.size;
-                link::transmit(&mut output, p);
+                link::transmit(output, p);
             }
         }
     }
@@ -42,9 +41,8 @@ impl engine::App for SinkApp {
     fn has_push(&self) -> bool { true }
     fn push(&self, app: &engine::AppState) {
         for input in app.input.values() {
-            let mut input = input.borrow_mut();
-            while !link::empty(&input) {
-                packet::free(link::receive(&mut input));
+            while !link::empty(input) {
+                packet::free(link::receive(input));
             }
         }
     }
@@ -65,14 +63,12 @@ impl engine::App for TeeApp {
     fn push(&self, app: &engine::AppState) {
         //let noutputs = app.output.len();
         for input in app.input.values() {
-            let mut input = input.borrow_mut();
-            while !link::empty(&input) {
-                let p = link::receive(&mut input);
+            while !link::empty(input) {
+                let p = link::receive(input);
                 //let mut outn = 0;
                 for output in app.output.values() {
-                    let mut output = output.borrow_mut();
                     //outn += 1;
-                    link::transmit(&mut output, packet::clone(&p));
+                    link::transmit(output, packet::clone(&p));
                     //if outn == noutputs { packet::clone(&p) } else { p }
                 }
                 packet::free(p);
@@ -95,21 +91,19 @@ impl engine::App for SourceSinkApp {
     fn has_pull(&self) -> bool { true }
     fn pull(&self, app: &engine::AppState) {
         for output in app.output.values() {
-            let mut output = output.borrow_mut();
             for _ in 0..engine::PULL_NPACKETS {
                 let mut p = packet::allocate();
                 lib::fill(&mut p.data, self.size as usize, 0);
                 p.length = self.size;
-                link::transmit(&mut output, p);
+                link::transmit(output, p);
             }
         }
     }
     fn has_push(&self) -> bool { true }
     fn push(&self, app: &engine::AppState) {
         for input in app.input.values() {
-            let mut input = input.borrow_mut();
-            while !link::empty(&input) {
-                packet::free(link::receive(&mut input));
+            while !link::empty(input) {
+                packet::free(link::receive(input));
             }
         }
     }
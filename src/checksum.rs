@@ -7,11 +7,20 @@ use super::lib;
 //
 //  ipsum(data: &[u8], length: usize, initial: u16) -> checksum: u16
 //    return the ones-complement checksum for the given region of memory
+//  Checksum - incremental accumulator for checksumming data that arrives in
+//    separately-sized chunks (e.g. scatter-gathered packet regions) without
+//    requiring each chunk but the last to have even length
 
-// Reference implementation in Rust.
-fn checksum_rust(data: &[u8], length: usize) -> u16 {
+// Reference implementation in Rust, used by the selftests below as the
+// bit-exact oracle that both the asm kernels and checksum_portable() must
+// agree with.
+fn checksum_rust(data: &[u8], length: usize, initial: u16) -> u16 {
     let ptr: *const u8 = data.as_ptr();
-    let mut csum: u64 = 0;
+    // The asm kernels byte-swap 'initial' (xchg al, ah / rev16) before
+    // adding it into their running sum; match that here by pre-swapping
+    // 'initial' the same way so this reference implementation agrees with
+    // them bit-for-bit.
+    let mut csum: u64 = lib::ntohs(initial) as u64;
     let mut i = length;
     while i > 1 {
         let word = unsafe { *(ptr.offset((length-i) as isize) as *const u16) };
@@ -29,6 +38,50 @@ fn checksum_rust(data: &[u8], length: usize) -> u16 {
     lib::ntohs(!csum as u16 & 0xffff)
 }
 
+// Portable, high-throughput implementation: used as the checksum() fallback
+// on architectures without a hand-written asm kernel (see below), and
+// available to benchmark against the asm on x86_64/aarch64.
+//
+// The asm kernels fold every word through a single adc dependency chain,
+// which stalls on carry propagation. Here we instead sum
+// 16-bit little-endian words widened to u64 into 4 independent
+// accumulators, each consuming every 4th word, inside an unrolled loop over
+// the body; since each accumulator only ever sees a quarter of the words
+// and a u64 can't overflow until ~2^48 of them, no carry handling is needed
+// inside the loop, and the independent lanes let LLVM autovectorize this to
+// SSE2/AVX2/NEON. The lanes are added together at the end, folded down to
+// 16 bits the same way checksum_rust does, one's-complemented, and
+// byte-swapped to host order.
+fn checksum_portable(data: &[u8], length: usize, initial: u16) -> u16 {
+    let ptr: *const u8 = data.as_ptr();
+    let mut lanes: [u64; 4] = [lib::ntohs(initial) as u64, 0, 0, 0];
+    let mut i = 0;
+    while i + 8 <= length {
+        for (lane, slot) in lanes.iter_mut().enumerate() {
+            let word = unsafe { *(ptr.add(i + lane * 2) as *const u16) };
+            *slot += word as u64;
+        }
+        i += 8;
+    }
+    let mut csum = lanes[0] + lanes[1] + lanes[2] + lanes[3];
+    // Trailing 1-7 bytes: same word-at-a-time scalar handling as
+    // checksum_rust.
+    while length - i > 1 {
+        let word = unsafe { *(ptr.add(i) as *const u16) };
+        csum += word as u64;
+        i += 2;
+    }
+    if length - i == 1 {
+        csum += data[length-1] as u64;
+    }
+    loop {
+        let carry = csum >> 16;
+        if carry == 0 { break; }
+        csum = (csum & 0xffff) + carry;
+    }
+    lib::ntohs(!csum as u16 & 0xffff)
+}
+
 // ipsum: return the ones-complement checksum for the given region of memory
 //
 // data is a byte slice to be checksummed.
@@ -59,6 +112,88 @@ pub fn ipsum(data: &[u8], length: usize, initial: u16) -> u16 {
     unsafe { checksum(data, length, initial) }
 }
 
+// Checksum: incremental ones-complement checksum accumulator.
+//
+// ipsum()'s 'initial' chaining only produces correct results when every
+// intermediate block has even length, because an odd-length block leaves
+// the 16-bit word boundary misaligned for the next call. Checksum fixes
+// that by tracking, across update() calls, whether an odd trailing byte is
+// still waiting to be paired with the next chunk's first byte, so fragments
+// of arbitrary length can be fed in one after another (e.g. scatter-
+// gathered packet header/payload regions) without pre-concatenating them
+// or restricting callers to even-length chunks.
+//
+// Modeled on the Hasher-style incremental hashers in std::hash, but
+// returns the 16-bit IP checksum rather than a generic 64-bit hash:
+//
+//   let mut c = checksum::Checksum::new();
+//   c.update(&header);
+//   c.update(&payload);
+//   let sum = c.finish();
+pub struct Checksum {
+    // Running sum of whole 16-bit words seen so far, in the same
+    // pre-byte-swapped form checksum_rust accumulates (see its comment).
+    acc: u64,
+    // An odd trailing byte from the end of the most recent update(), not
+    // yet paired with a following byte into a 16-bit word. None means the
+    // total number of bytes seen so far is even.
+    held: Option<u8>
+}
+
+impl Checksum {
+    pub fn new() -> Checksum {
+        Checksum { acc: 0, held: None }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut data = data;
+        if let Some(byte) = self.held.take() {
+            match data.split_first() {
+                Some((&first, rest)) => {
+                    // Pair the held byte with this chunk's first byte into
+                    // the 16-bit word they would have formed had the
+                    // caller passed them in a single update() call, using
+                    // the same in-memory byte order the asm/portable
+                    // kernels read words in.
+                    self.acc += u16::from_ne_bytes([byte, first]) as u64;
+                    data = rest;
+                }
+                None => {
+                    // Empty chunk: nothing to pair the held byte with yet.
+                    self.held = Some(byte);
+                    return;
+                }
+            }
+        }
+        let ptr = data.as_ptr();
+        let length = data.len();
+        let mut i = 0;
+        while length - i > 1 {
+            let word = unsafe { *(ptr.add(i) as *const u16) };
+            self.acc += word as u64;
+            i += 2;
+        }
+        self.held = if length - i == 1 { Some(data[length-1]) } else { None };
+    }
+
+    pub fn finish(&self) -> u16 {
+        let mut csum = self.acc;
+        if let Some(byte) = self.held {
+            csum += byte as u64;
+        }
+        loop {
+            let carry = csum >> 16;
+            if carry == 0 { break; }
+            csum = (csum & 0xffff) + carry;
+        }
+        lib::ntohs(!csum as u16 & 0xffff)
+    }
+}
+
+impl Default for Checksum {
+    fn default() -> Checksum { Checksum::new() }
+}
+
 #[cfg(target_arch="x86_64")]
 unsafe fn checksum(data: &[u8], length: usize, initial: u16) -> u16 {
     let mut _ptr = data.as_ptr();
@@ -212,6 +347,15 @@ rev16 w0, w0
     acc as u16
 }
 
+// Portable fallback for architectures without a hand-written asm kernel
+// above (e.g. riscv64, wasm32, 32-bit x86): delegate to the multi-lane
+// portable implementation, which LLVM can still autovectorize even without
+// hand-written asm.
+#[cfg(not(any(target_arch="x86_64", target_arch="aarch64")))]
+unsafe fn checksum(data: &[u8], length: usize, initial: u16) -> u16 {
+    checksum_portable(data, length, initial)
+}
+
 #[cfg(test)]
 mod selftest {
     use super::*;
@@ -232,7 +376,7 @@ mod selftest {
         ];
         for case in cases {
             for l in 0..=case.len() {
-                let n = checksum_rust(&case, l);
+                let n = checksum_rust(&case, l, 0);
                 println!("{:?} {} {}", &case, l, n);
                 assert_eq!(ipsum(&case, l, 0), n);
             }
@@ -250,7 +394,7 @@ mod selftest {
             for l in 0..=1500 { // Tune this down (to e.g. 63) for faster cases
                 let mut case = vec![0u8; l];
                 lib::random_bytes(&mut case, l);
-                let r = checksum_rust(&case, l);
+                let r = checksum_rust(&case, l, 0);
                 let n = ipsum(&case, l, 0);
                 if r != n {
                     println!("{:?} len={} ref={} asm={}", &case, l, r, n);
@@ -260,6 +404,59 @@ mod selftest {
         }
     }
 
+    #[test]
+    fn checksum_portable_random() {
+        let mut progress = 1;
+        for i in 1..=32 { // Crank this up to run more random test cases
+            if i >= progress {
+                println!("{}", progress);
+                progress *= 2;
+            }
+            for l in 0..=1500 { // Tune this down (to e.g. 63) for faster cases
+                let mut case = vec![0u8; l];
+                lib::random_bytes(&mut case, l);
+                let r = checksum_rust(&case, l, 0);
+                let n = checksum_portable(&case, l, 0);
+                if r != n {
+                    println!("{:?} len={} ref={} portable={}", &case, l, r, n);
+                    panic!("mismatch");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn checksum_streaming_random() {
+        let mut progress = 1;
+        for i in 1..=32 { // Crank this up to run more random test cases
+            if i >= progress {
+                println!("{}", progress);
+                progress *= 2;
+            }
+            for l in 0..=1500 { // Tune this down (to e.g. 63) for faster cases
+                let mut case = vec![0u8; l];
+                lib::random_bytes(&mut case, l);
+                let r = checksum_rust(&case, l, 0);
+                // Feed the case through Checksum split at arbitrary
+                // (including odd) offsets, to exercise the word-alignment
+                // bookkeeping across update() calls.
+                let mut c = Checksum::new();
+                let mut pos = 0;
+                while pos < l {
+                    let chunk_len = 1 + (case[pos] as usize % 7);
+                    let end = std::cmp::min(pos + chunk_len, l);
+                    c.update(&case[pos..end]);
+                    pos = end;
+                }
+                let n = c.finish();
+                if r != n {
+                    println!("{:?} len={} ref={} streaming={}", &case, l, r, n);
+                    panic!("mismatch");
+                }
+            }
+        }
+    }
+
     #[test]
     fn checksum_bench() {
         let nchunks = match std::env::var("RUSH_CHECKSUM_NCHUNKS") {
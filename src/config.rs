@@ -0,0 +1,148 @@
+use super::engine;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::error::Error;
+
+// APP NETWORK CONFIGURATION
+//
+// This module defines the data structure used to describe a desired app
+// network (the set of apps and the links between them), to be applied to
+// the engine via engine::configure().
+//
+//   Config - struct describing an app network
+//   new() -> Config - create an empty configuration
+//   app(&mut Config, name: &str, &AppConfig) -> Result<(), ConfigError>
+//   worker(&mut Config, name: &str, worker: usize) -> Result<(), ConfigError>
+//   link(&mut Config, spec: &str) -> Result<(), ConfigError>
+//   LinkSpec - struct describing the two endpoints of a link
+//   parse_link(spec: &str) -> Result<LinkSpec, ConfigError>
+//   ConfigError - reasons a Config (or its application) can be rejected
+
+#[derive(Clone)]
+pub struct Config<'a> {
+    // '+ Sync' because an app's AppConfig is read by configure() (to decide
+    // whether to restart a changed app) for as long as the app runs, and a
+    // running app may be on a worker thread started by engine::run_workers.
+    pub apps: HashMap<String, &'a (dyn engine::AppArg + Sync)>,
+    pub links: HashSet<String>,
+    // Worker thread (see engine::run_workers) each app is assigned to run
+    // on. Apps not named here run on worker 0, so a Config that never calls
+    // config::worker() behaves exactly as before run_workers() existed.
+    pub workers: HashMap<String, usize>
+}
+
+pub fn new<'a>() -> Config<'a> {
+    Config { apps: HashMap::new(), links: HashSet::new(), workers: HashMap::new() }
+}
+
+// Add an app instance, named 'name' and configured by 'conf', to 'config'.
+pub fn app<'a, A: engine::AppArg + Sync>(config: &mut Config<'a>, name: &str, conf: &'a A)
+                                  -> Result<(), ConfigError> {
+    if config.apps.contains_key(name) {
+        return Err(ConfigError::DuplicateApp(name.to_string()));
+    }
+    config.apps.insert(name.to_string(), conf);
+    Ok(())
+}
+
+// Assign the app named 'name' to run on 'worker' (see engine::run_workers).
+// 'name' must already have been added via config::app(). Links between apps
+// assigned to different workers are carried over a shared link::Link, which
+// is safe for exactly one producer and one consumer (see link.rs); apps
+// pulling/pushing the same link from more than one worker is not.
+pub fn worker(config: &mut Config, name: &str, worker: usize) -> Result<(), ConfigError> {
+    if !config.apps.contains_key(name) {
+        return Err(ConfigError::UnknownApp(name.to_string()));
+    }
+    config.workers.insert(name.to_string(), worker);
+    Ok(())
+}
+
+// Add a link, described by 'spec' (e.g. "source.output -> sink.input"), to
+// 'config'. Both endpoint apps must already have been added, and neither the
+// output nor the input port of the spec may already be connected by another
+// link in 'config'.
+pub fn link(config: &mut Config, spec: &str) -> Result<(), ConfigError> {
+    let parsed = parse_link(spec)?;
+    if !config.apps.contains_key(&parsed.from) {
+        return Err(ConfigError::UnknownApp(parsed.from));
+    }
+    if !config.apps.contains_key(&parsed.to) {
+        return Err(ConfigError::UnknownApp(parsed.to));
+    }
+    for existing in &config.links {
+        let other = parse_link(existing)?;
+        if other.from == parsed.from && other.output == parsed.output {
+            return Err(ConfigError::PortInUse { app: parsed.from, port: parsed.output });
+        }
+        if other.to == parsed.to && other.input == parsed.input {
+            return Err(ConfigError::PortInUse { app: parsed.to, port: parsed.input });
+        }
+    }
+    config.links.insert(spec.to_string());
+    Ok(())
+}
+
+// The two endpoints of a link, as named by a link spec.
+pub struct LinkSpec {
+    pub from: String, pub output: String,
+    pub to: String, pub input: String
+}
+
+// Parse a link spec of the form "<app>.<port> -> <app>.<port>".
+pub fn parse_link(spec: &str) -> Result<LinkSpec, ConfigError> {
+    let malformed = || ConfigError::InvalidLinkSpec(spec.to_string());
+    let mut sides = spec.splitn(2, "->").map(str::trim);
+    let mut from = sides.next().ok_or_else(malformed)?.splitn(2, '.');
+    let mut to = sides.next().ok_or_else(malformed)?.splitn(2, '.');
+    Ok(LinkSpec {
+        from: from.next().ok_or_else(malformed)?.to_string(),
+        output: from.next().ok_or_else(malformed)?.to_string(),
+        to: to.next().ok_or_else(malformed)?.to_string(),
+        input: to.next().ok_or_else(malformed)?.to_string()
+    })
+}
+
+// Reasons a Config, or its application to a running engine, can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    // A link spec, or config::worker(), named an app that was never added
+    // to the configuration.
+    UnknownApp(String),
+    // config::app() was called twice with the same name in one Config.
+    DuplicateApp(String),
+    // A link spec could not be parsed as "<app>.<port> -> <app>.<port>".
+    InvalidLinkSpec(String),
+    // The named output or input port is already connected by another link.
+    PortInUse { app: String, port: String },
+    // The app network's links don't admit a schedulable push order: some
+    // app's input is fed, directly or transitively, by its own output (see
+    // engine::configure's compute_breathe_order).
+    Cycle,
+    // AppConfig::new() panicked while constructing the named app (see
+    // engine::start_app), e.g. a backend that isn't implemented yet.
+    // 'message' is the panic payload, if it was a string.
+    AppInit { name: String, message: String }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::UnknownApp(name) =>
+                write!(f, "unknown app {:?}", name),
+            ConfigError::DuplicateApp(name) =>
+                write!(f, "app {:?} already added to this configuration", name),
+            ConfigError::InvalidLinkSpec(spec) =>
+                write!(f, "malformed link spec {:?}", spec),
+            ConfigError::PortInUse { app, port } =>
+                write!(f, "port {}.{} is already connected", app, port),
+            ConfigError::Cycle =>
+                write!(f, "app network contains a cycle that cannot be scheduled"),
+            ConfigError::AppInit { name, message } =>
+                write!(f, "app {:?} failed to start: {}", name, message)
+        }
+    }
+}
+
+impl Error for ConfigError {}
@@ -0,0 +1,143 @@
+use super::packet;
+use super::link;
+use super::engine;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::cell::RefCell;
+
+// HOST-NIC DEVICE DRIVER ABSTRACTION
+//
+// This module decouples apps that move packets in and out of the kernel
+// (TAP, AF_PACKET, AF_XDP, ...) from the blocking syscalls that doing so
+// requires. Each backend runs its own I/O thread which owns the file
+// descriptor and exchanges packets with the engine breathe loop through a
+// pair of bounded single-producer/single-consumer queues, so pull()/push()
+// never block on the kernel.
+//
+//   Device - trait implemented by a host-NIC backend
+//   SpscQueue<T> - bounded, blocking-free-on-the-hot-side SPSC queue
+//   ChannelDevice - Device built from a pair of SpscQueues and an I/O thread
+//   DeviceApp - generic App that drains/fills a Device's queues
+
+// A host-NIC backend, as seen by the engine.
+//
+// receive()/transmit() must never block: they only touch the SpscQueues
+// that the backend's I/O thread is the other end of.
+// Send because a DeviceApp (and the Box<dyn Device> inside it) may run on
+// any one of engine::run_workers()'s threads.
+pub trait Device: Send {
+    fn receive(&mut self) -> Option<Box<packet::Packet>>;
+    fn transmit(&mut self, p: Box<packet::Packet>);
+    fn mtu(&self) -> usize;
+    fn link_up(&self) -> bool;
+}
+
+// Bounded SPSC queue of packets, shared between an app (DeviceApp) and a
+// backend's I/O thread. Uses a Mutex+Condvar rather than a lock-free ring
+// like link::Link's, so that the I/O thread can sleep in pop_blocking()
+// until there is a packet to transmit.
+pub struct SpscQueue<T> {
+    queue: Mutex<VecDeque<T>>,
+    not_empty: Condvar,
+    capacity: usize
+}
+
+impl<T> SpscQueue<T> {
+    pub fn new(capacity: usize) -> Arc<SpscQueue<T>> {
+        Arc::new(SpscQueue {
+            queue: Mutex::new(VecDeque::with_capacity(capacity)),
+            not_empty: Condvar::new(),
+            capacity
+        })
+    }
+
+    // Non-blocking push. Returns Err(item), handing ownership back, if the
+    // queue is full, so the caller can decide how to handle backpressure.
+    pub fn try_push(&self, item: T) -> Result<(), T> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.len() >= self.capacity { return Err(item); }
+        queue.push_back(item);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    // Non-blocking pop, used from the engine breathe loop.
+    pub fn try_pop(&self) -> Option<T> {
+        self.queue.lock().unwrap().pop_front()
+    }
+
+    // Blocking pop, used by a backend's I/O thread waiting for packets to
+    // transmit.
+    pub fn pop_blocking(&self) -> T {
+        let mut queue = self.queue.lock().unwrap();
+        while queue.is_empty() {
+            queue = self.not_empty.wait(queue).unwrap();
+        }
+        queue.pop_front().unwrap()
+    }
+}
+
+// A Device whose receive()/transmit() only drain/fill a pair of SpscQueues;
+// the I/O thread (spawned by the concrete backend, e.g. tap::Tap) is the
+// other end of those queues and owns the actual file descriptor.
+pub struct ChannelDevice {
+    rx: Arc<SpscQueue<Box<packet::Packet>>>,
+    tx: Arc<SpscQueue<Box<packet::Packet>>>,
+    mtu: usize,
+    link_up: Arc<AtomicBool>
+}
+
+impl ChannelDevice {
+    pub fn new(rx: Arc<SpscQueue<Box<packet::Packet>>>,
+               tx: Arc<SpscQueue<Box<packet::Packet>>>,
+               mtu: usize,
+               link_up: Arc<AtomicBool>) -> ChannelDevice {
+        ChannelDevice { rx, tx, mtu, link_up }
+    }
+}
+
+impl Device for ChannelDevice {
+    fn receive(&mut self) -> Option<Box<packet::Packet>> { self.rx.try_pop() }
+    fn transmit(&mut self, p: Box<packet::Packet>) {
+        if let Err(p) = self.tx.try_push(p) {
+            // TX queue to the I/O thread is full; drop rather than block.
+            packet::free(p);
+        }
+    }
+    fn mtu(&self) -> usize { self.mtu }
+    fn link_up(&self) -> bool { self.link_up.load(Ordering::Relaxed) }
+}
+
+// Generic app that drives any Device, draining received packets onto its
+// "output" link and filling the device's TX queue from its "input" link.
+pub struct DeviceApp { device: RefCell<Box<dyn Device>> }
+
+impl DeviceApp {
+    pub fn new(device: Box<dyn Device>) -> DeviceApp {
+        DeviceApp { device: RefCell::new(device) }
+    }
+}
+
+impl engine::App for DeviceApp {
+    fn pull(&self, app: &engine::AppState) {
+        if let Some(output) = app.output.get("output") {
+            let mut device = self.device.borrow_mut();
+            for _ in 0..engine::PULL_NPACKETS {
+                match device.receive() {
+                    Some(p) => link::transmit(output, p),
+                    None => break
+                }
+            }
+        }
+    }
+    fn push(&self, app: &engine::AppState) {
+        if let Some(input) = app.input.get("input") {
+            let mut device = self.device.borrow_mut();
+            while !link::empty(input) {
+                device.transmit(link::receive(input));
+            }
+        }
+    }
+}
@@ -7,12 +7,17 @@
 //   stats() -> EngineStats - get engine statistics
 //   EngineState - struct representing engine state
 //   init() -> EngineState - initialize engine (can only be called once)
-//   SharedLink - type for shared links (between apps, also in EngineState)   
+//   SharedLink - type for shared links (between apps, also in EngineState)
 //   AppState - struct representing an app in the current app network
 //   App, AppConfig - traits that defines an app, and its configuration
 //   PULL_NPACKETS - number of packets to be inhaled in app’s pull() methods
-//   configure(&mut EngineState, &config) - apply configuration to app network
-//   main(&EngineState, Options) - run the engine breathe loop
+//   configure(&mut EngineState, &config) - apply configuration to app network,
+//     computing EngineState's inhale/exhale breathe order (or returning
+//     config::ConfigError::Cycle if the app network's links don't form a
+//     schedulable order)
+//   main(&mut EngineState, Options, management) - run the engine breathe loop
+//   run_workers(&EngineState, Duration) - run each config::worker()'s
+//     apps on its own thread, for the given duration
 //   Options - engine breathe loop options
 //   now() -> Instant - return current monotonic engine time
 //   timeout(Duration) -> [()->bool] - make timer returning true after duration
@@ -21,35 +26,66 @@
 
 use super::link;
 use super::config;
+use super::management;
 
 use std::collections::HashMap;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 use std::thread::sleep;
 use std::cmp::min;
 
 // Counters for global engine statistics.
+//
+// Backed by atomics (rather than a static mut, as in the rest of this
+// module) because, unlike breathe()'s other bookkeeping, these are bumped
+// from packet::free() - reachable from any worker thread started by
+// run_workers(), not just the thread running main()'s breathe loop.
 pub struct EngineStats {
     pub breaths: u64,  // Total breaths taken
     pub frees: u64,    // Total packets freed
     pub freebits: u64, // Total packet bits freed (for 10GbE)
     pub freebytes: u64 // Total packet bytes freed
 }
-static mut STATS: EngineStats = EngineStats {
-    breaths: 0, frees: 0, freebits: 0, freebytes: 0
+struct AtomicStats {
+    breaths: AtomicU64, frees: AtomicU64, freebits: AtomicU64, freebytes: AtomicU64
+}
+static STATS: AtomicStats = AtomicStats {
+    breaths: AtomicU64::new(0), frees: AtomicU64::new(0),
+    freebits: AtomicU64::new(0), freebytes: AtomicU64::new(0)
 };
-pub fn add_frees    ()           { unsafe { STATS.frees += 1 } }
-pub fn add_freebytes(bytes: u64) { unsafe { STATS.freebytes += bytes; } }
-pub fn add_freebits (bits: u64)  { unsafe { STATS.freebits += bits; } }
-pub fn stats() -> &'static EngineStats { unsafe { &STATS } }
+pub fn add_frees    ()           { STATS.frees.fetch_add(1, Ordering::Relaxed); }
+pub fn add_freebytes(bytes: u64) { STATS.freebytes.fetch_add(bytes, Ordering::Relaxed); }
+pub fn add_freebits (bits: u64)  { STATS.freebits.fetch_add(bits, Ordering::Relaxed); }
+fn add_breath()                  { STATS.breaths.fetch_add(1, Ordering::Relaxed); }
+// Aggregate snapshot of the counters above, across every worker thread.
+pub fn stats() -> EngineStats {
+    EngineStats {
+        breaths: STATS.breaths.load(Ordering::Relaxed),
+        frees: STATS.frees.load(Ordering::Relaxed),
+        freebits: STATS.freebits.load(Ordering::Relaxed),
+        freebytes: STATS.freebytes.load(Ordering::Relaxed)
+    }
+}
 
 // Global engine state; singleton obtained via engine::init()
 //
-// The set of all active apps and links in the system, indexed by name.
+// The set of all active apps and links in the system, indexed by name, plus
+// the most recently computed breathe order (see configure()'s
+// compute_breathe_order).
 pub struct EngineState<'state> {
     pub link_table: HashMap<String, SharedLink>,
-    pub app_table: HashMap<String, AppState<'state>>
+    pub app_table: HashMap<String, AppState<'state>>,
+    // App names in the order their pull() should be called: any order
+    // works, since pull() only ever produces packets and never depends on
+    // another app's output this breath, so this is just app_table's keys
+    // sorted for a deterministic report.
+    pub inhale: Vec<String>,
+    // App names in the order their push() should be called, so that if a
+    // link feeds app B's input from app A's output, A is pushed first and
+    // B can act on what A produced in the same breath. A configure() call
+    // that fails with a link cycle leaves this (and 'inhale') unchanged.
+    pub exhale: Vec<String>
 }
 static mut INIT: bool = false;
 pub fn init<'state>() -> EngineState<'state> {
@@ -57,34 +93,52 @@ pub fn init<'state>() -> EngineState<'state> {
     unsafe { INIT = true; }
     EngineState {
         app_table: HashMap::new(),
-        link_table: HashMap::new()
+        link_table: HashMap::new(),
+        inhale: Vec::new(),
+        exhale: Vec::new()
     }
 }
 
 // Type for links shared between apps.
 //
-// Links are borrowed at runtime by apps to perform packet I/O, or via the
-// global engine state (to query link statistics etc.)
-pub type SharedLink = Rc<RefCell<link::Link>>;
+// Links are passed at runtime to apps to perform packet I/O, or read via the
+// global engine state (to query link statistics etc.) link::Link is its own
+// single-producer/single-consumer channel (see link.rs), so an Arc with no
+// RefCell is enough to share one between whichever two apps it connects -
+// including across the worker-thread boundary run_workers() introduces.
+pub type SharedLink = Arc<link::Link>;
 
 // State for a sigle app instance managed by the engine
 //
-// Tracks a reference to the AppConfig used to instantiate the app, and maps of
+// Tracks a reference to the AppConfig used to instantiate the app, the
+// worker thread (see config::worker()) it is assigned to run on, and maps of
 // its active input and output links.
 pub struct AppState<'state> {
     pub app: Box<dyn App>,
-    pub conf: &'state dyn AppArg,
+    pub conf: &'state (dyn AppArg + Sync),
+    pub worker: usize,
     pub input: HashMap<String, SharedLink>,
     pub output: HashMap<String, SharedLink>
 }
 
+// SAFETY: run_workers() partitions AppStates by 'worker' and hands each
+// partition's &AppState to exactly one thread (see run_workers), so two
+// threads never call pull()/push() on the same AppState concurrently - the
+// discipline &AppState: Send would otherwise need App: Sync (stronger than
+// the App: Send this crate actually relies on) to verify on its own.
+unsafe impl Sync for AppState<'_> {}
+
 // Callbacks that can be implented by apps
 //
 //   pull: inhale packets into the app network (put them onto output links)
 //   push: exhale packets out the the app network (move them from input links
 //         to output links, or peripheral device queues)
 //   stop: stop the app (deinitialize)
-pub trait App {
+//
+// App: Send because an AppState (and the Box<dyn App> inside it) may be
+// moved onto a worker thread by run_workers(); an app's own state must never
+// be reachable from more than one worker at a time (only its links may be).
+pub trait App: Send {
     fn pull(&self, _app: &AppState) {}
     fn push(&self, _app: &AppState) {} // Exhale packets from apps.input
     fn stop(&self) {}
@@ -116,13 +170,15 @@ impl<T: AppConfig> AppArg for T { }
 // Configure the running app network to match (new) config.
 //
 // Successive calls to configure() will migrate from the old to the
-// new app network by making the changes needed.
+// new app network by making the changes needed. Returns a ConfigError if
+// 'config' is malformed and leaves the link table in whatever partial state
+// was reached before the offending link.
 pub fn configure<'state>(state: &mut EngineState<'state>,
-                         config: &config::Config<'state>) {
+                         config: &config::Config<'state>) -> Result<(), config::ConfigError> {
     // First determine the links that are going away and remove them.
     for link in state.link_table.clone().keys() {
         if config.links.get(link).is_none() {
-            unlink_apps(state, link)
+            unlink_apps(state, link)?;
         }
     }
     // Do the same for apps.
@@ -137,23 +193,89 @@ pub fn configure<'state>(state: &mut EngineState<'state>,
     // Start new apps.
     for (name, &arg) in config.apps.iter() {
         if state.app_table.get(name).is_none() {
-            start_app(state, name, arg)
+            let worker = config.workers.get(name).copied().unwrap_or(0);
+            start_app(state, name, arg, worker)?;
         }
     }
     // Rebuild links.
     for link in config.links.iter() {
-        link_apps(state, link);
+        link_apps(state, link)?;
     }
+    let (inhale, exhale) = compute_breathe_order(state)?;
+    state.inhale = inhale;
+    state.exhale = exhale;
+    Ok(())
+}
+
+// Compute the order breathe_apps() should pull and push apps in (see
+// EngineState::inhale/exhale).
+//
+// 'inhale' order doesn't actually matter - pull() only ever adds packets to
+// a link, it never reads one - so it's just the app names sorted for a
+// deterministic report. 'exhale' does matter: if a link connects A's output
+// to B's input, B's push() should run after A's push() has had a chance to
+// put this breath's packets on that link. That's a topological sort of the
+// app network by link, computed here via Kahn's algorithm (processing
+// indegree-0 apps in name order, for a deterministic result); if the graph
+// contains a cycle, no such order exists and this returns ConfigError::Cycle
+// instead, leaving the caller's previous order in place.
+fn compute_breathe_order(state: &EngineState) -> Result<(Vec<String>, Vec<String>), config::ConfigError> {
+    let mut inhale: Vec<String> = state.app_table.keys().cloned().collect();
+    inhale.sort();
+
+    let mut indegree: HashMap<String, usize> =
+        state.app_table.keys().map(|name| (name.clone(), 0)).collect();
+    let mut dependents: HashMap<String, Vec<String>> =
+        state.app_table.keys().map(|name| (name.clone(), Vec::new())).collect();
+    for link in state.link_table.keys() {
+        let parsed = config::parse_link(link)?;
+        dependents.get_mut(&parsed.from)
+            .ok_or_else(|| config::ConfigError::UnknownApp(parsed.from.clone()))?
+            .push(parsed.to.clone());
+        *indegree.get_mut(&parsed.to)
+            .ok_or_else(|| config::ConfigError::UnknownApp(parsed.to.clone()))? += 1;
+    }
+
+    let mut ready: std::collections::BTreeSet<String> =
+        indegree.iter().filter(|&(_, &d)| d == 0).map(|(name, _)| name.clone()).collect();
+    let mut exhale = Vec::new();
+    while let Some(name) = ready.iter().next().cloned() {
+        ready.remove(&name);
+        for dependent in &dependents[&name] {
+            let degree = indegree.get_mut(dependent).unwrap();
+            *degree -= 1;
+            if *degree == 0 { ready.insert(dependent.clone()); }
+        }
+        exhale.push(name);
+    }
+    if exhale.len() != state.app_table.len() {
+        return Err(config::ConfigError::Cycle);
+    }
+    Ok((inhale, exhale))
 }
 
 // Insert new app instance into network.
-fn start_app<'state>(state: &mut EngineState<'state>,
-                     name: &str, conf: &'state dyn AppArg) {
+//
+// Some AppConfig::new() impls panic rather than construct an app for a
+// backend that isn't implemented yet (see af_xdp::AfXdp), so a bad config
+// entry can't otherwise be told apart from a crashing engine. Catch that
+// here and report it the same way as any other rejected config, rather than
+// taking the whole engine down over one unimplemented app.
+fn start_app<'state>(state: &mut EngineState<'state>, name: &str,
+                     conf: &'state (dyn AppArg + Sync), worker: usize)
+                     -> Result<(), config::ConfigError> {
+    let app = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| conf.new()))
+        .map_err(|payload| {
+            let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panicked with a non-string payload".to_string());
+            config::ConfigError::AppInit { name: name.to_string(), message }
+        })?;
     state.app_table.insert(name.to_string(),
-                           AppState { app: conf.new(),
-                                      conf: conf,
+                           AppState { app, conf, worker,
                                       input: HashMap::new(),
                                       output: HashMap::new() });
+    Ok(())
 }
 
 // Remove app instance from network.
@@ -162,31 +284,38 @@ fn stop_app (state: &mut EngineState, name: &str) {
 }
 
 // Allocate a fresh shared link.
-fn new_shared_link() -> SharedLink { Rc::new(RefCell::new(link::new())) }
+fn new_shared_link() -> SharedLink { Arc::new(link::new()) }
 
 // Link two apps in the network.
-fn link_apps(state: &mut EngineState, spec: &str) {
+fn link_apps(state: &mut EngineState, spec: &str) -> Result<(), config::ConfigError> {
+    let parsed = config::parse_link(spec)?;
     let link = state.link_table.entry(spec.to_string())
         .or_insert_with(new_shared_link);
-    let spec = config::parse_link(spec);
-    state.app_table.get_mut(&spec.from).unwrap()
-        .output.insert(spec.output, link.clone());
-    state.app_table.get_mut(&spec.to).unwrap()
-        .input.insert(spec.input, link.clone());
+    state.app_table.get_mut(&parsed.from)
+        .ok_or_else(|| config::ConfigError::UnknownApp(parsed.from.clone()))?
+        .output.insert(parsed.output, link.clone());
+    state.app_table.get_mut(&parsed.to)
+        .ok_or_else(|| config::ConfigError::UnknownApp(parsed.to.clone()))?
+        .input.insert(parsed.input, link.clone());
+    Ok(())
 }
 
 // Remove link between two apps.
-fn unlink_apps(state: &mut EngineState, spec: &str) {
+fn unlink_apps(state: &mut EngineState, spec: &str) -> Result<(), config::ConfigError> {
     state.link_table.remove(spec);
-    let spec = config::parse_link(spec);
-    state.app_table.get_mut(&spec.from).unwrap()
-        .output.remove(&spec.output);
-    state.app_table.get_mut(&spec.to).unwrap()
-        .input.remove(&spec.input);
+    let parsed = config::parse_link(spec)?;
+    if let Some(app) = state.app_table.get_mut(&parsed.from) { app.output.remove(&parsed.output); }
+    if let Some(app) = state.app_table.get_mut(&parsed.to) { app.input.remove(&parsed.input); }
+    Ok(())
 }
 
 // Call this to “run snabb”.
-pub fn main(state: &EngineState, options: Option<Options>) {
+//
+// 'management', if given, is polled once per breath (see management.rs) so a
+// remote peer can query link/engine statistics, or trigger a staged
+// reconfiguration, without ever blocking packet processing.
+pub fn main<'state>(state: &mut EngineState<'state>, options: Option<Options>,
+                    management: Option<&mut management::Server<'state>>) {
     let options = match options {
         Some(options) => options,
         None => Options{..Default::default()}
@@ -198,21 +327,68 @@ pub fn main(state: &EngineState, options: Option<Options>) {
         let deadline = timeout(duration);
         done = Some(Box::new(move |_, _| deadline()));
     }
+    let mut management = management;
 
-    breathe(state);
+    breathe(&*state);
     while match &done {
-        Some(done) => !done(state, unsafe {&STATS}),
+        Some(done) => !done(&*state, &stats()),
         None => true
     } {
+        if let Some(server) = &mut management { server.poll(state); }
         pace_breathing();
-        breathe(state);
+        breathe(&*state);
     }
     if !options.no_report {
         if options.report_load  { report_load(); }
-        if options.report_links { report_links(state); }
+        if options.report_links { report_links(&*state); }
     }
 
-    unsafe { MONOTONIC_NOW = None; }
+    MONOTONIC_NOW.with(|now| now.set(None));
+}
+
+// Run the app network across multiple threads instead of engine::main's
+// single breathe loop: every distinct worker id assigned via
+// config::worker() (apps left unassigned default to worker 0, see
+// config::Config::workers) gets its own thread, running breathe() over just
+// the AppStates pinned to that worker, for 'duration'. Links that cross a
+// worker boundary are unaffected - a SharedLink is an Arc<link::Link>, and
+// link::Link is sound as a channel shared between exactly one producer
+// thread and one consumer thread (see link.rs) - but an app itself must
+// never be split across two workers, which is why config::worker() assigns
+// whole apps, not individual ports.
+//
+// Unlike main(), there is no 'management' or 'done' predicate here: the
+// worker threads are joined unconditionally after 'duration' elapses, same
+// as passing Options{duration, ..} to main() would do for a single thread.
+pub fn run_workers(state: &EngineState, duration: Duration) {
+    let deadline = Instant::now() + duration;
+    let workers: std::collections::BTreeSet<usize> =
+        state.app_table.values().map(|app| app.worker).collect();
+
+    let mut pull_by_worker: HashMap<usize, Vec<&AppState>> = HashMap::new();
+    for app in state.app_table.values() {
+        pull_by_worker.entry(app.worker).or_insert_with(Vec::new).push(app);
+    }
+    // Push order follows state.exhale, same as breathe(); here it's also
+    // partitioned by worker, so each worker still pushes its own apps in
+    // their relative exhale order.
+    let mut push_by_worker: HashMap<usize, Vec<&AppState>> = HashMap::new();
+    for name in &state.exhale {
+        let app = &state.app_table[name];
+        push_by_worker.entry(app.worker).or_insert_with(Vec::new).push(app);
+    }
+
+    std::thread::scope(|scope| {
+        for worker in workers {
+            let pull_apps = pull_by_worker.remove(&worker).unwrap_or_default();
+            let push_apps = push_by_worker.remove(&worker).unwrap_or_default();
+            scope.spawn(move || {
+                while Instant::now() < deadline {
+                    breathe_apps(&pull_apps, &push_apps);
+                }
+            });
+        }
+    });
 }
 
 // Engine breathe loop Options
@@ -233,9 +409,17 @@ pub struct Options {
 
 // Return current monotonic time.
 // Can be used to drive timers in apps.
-static mut MONOTONIC_NOW: Option<Instant> = None;
+//
+// Cached per-thread (rather than in a single global, as the rest of this
+// module's bookkeeping is) because breathe() updates it every breath, and
+// run_workers() calls breathe_apps() from several threads at once; a global
+// would let one worker's breath overwrite the timestamp another worker's
+// apps are mid-read of.
+thread_local! {
+    static MONOTONIC_NOW: std::cell::Cell<Option<Instant>> = std::cell::Cell::new(None);
+}
 pub fn now() -> Instant {
-    match unsafe { MONOTONIC_NOW } {
+    match MONOTONIC_NOW.with(|now| now.get()) {
         Some(instant) => instant,
         None => Instant::now()
     }
@@ -248,16 +432,30 @@ pub fn timeout(duration: Duration) -> Box<dyn Fn() -> bool> {
     Box::new(move || now() > deadline)
 }
 
-// Perform a single breath (inhale / exhale)
+// Perform a single breath (inhale / exhale) over every app in the network.
+//
+// Pull order doesn't matter (see compute_breathe_order), so pull_apps is
+// just every app; push order does, so push_apps follows state.exhale.
 fn breathe(state: &EngineState) {
-    unsafe { MONOTONIC_NOW = Some(Instant::now()); }
-    for app in state.app_table.values() {
-        app.app.pull(&app);
+    let pull_apps: Vec<&AppState> = state.app_table.values().collect();
+    let push_apps: Vec<&AppState> =
+        state.exhale.iter().map(|name| &state.app_table[name]).collect();
+    breathe_apps(&pull_apps, &push_apps);
+}
+
+// Perform a single breath over just 'pull_apps'/'push_apps' - either every
+// app in the network, pulled in any order and pushed in exhale order
+// (breathe(), the main() loop), or the subset pinned to one worker, still
+// pushed in exhale's relative order for that worker (run_workers()).
+fn breathe_apps(pull_apps: &[&AppState], push_apps: &[&AppState]) {
+    MONOTONIC_NOW.with(|now| now.set(Some(Instant::now())));
+    for app in pull_apps {
+        app.app.pull(app);
     }
-    for app in state.app_table.values() {
-        app.app.push(&app);
+    for app in push_apps {
+        app.app.push(app);
     }
-    unsafe { STATS.breaths += 1; }
+    add_breath();
 }
 
 // Breathing regluation to reduce CPU usage when idle by calling sleep.
@@ -271,14 +469,15 @@ static mut LASTFREES: u64 = 0;
 static mut SLEEP: u64 = 0;
 const MAXSLEEP: u64 = 100;
 fn pace_breathing() {
+    let frees = STATS.frees.load(Ordering::Relaxed);
     unsafe {
-        if LASTFREES == STATS.frees {
+        if LASTFREES == frees {
             SLEEP = min(SLEEP + 1, MAXSLEEP);
             sleep(Duration::from_micros(SLEEP));
         } else {
             SLEEP /= 2;
         }
-        LASTFREES = STATS.frees;
+        LASTFREES = frees;
     }
 }
 
@@ -294,11 +493,11 @@ static mut REPORTEDFREEBITS: u64 = 0;
 static mut REPORTEDFREEBYTES: u64 = 0;
 static mut REPORTEDBREATHS: u64 = 0;
 pub fn report_load() {
+    let frees = STATS.frees.load(Ordering::Relaxed);
+    let freebits = STATS.freebits.load(Ordering::Relaxed);
+    let freebytes = STATS.freebytes.load(Ordering::Relaxed);
+    let breaths = STATS.breaths.load(Ordering::Relaxed);
     unsafe {
-        let frees = STATS.frees;
-        let freebits = STATS.freebits;
-        let freebytes = STATS.freebytes;
-        let breaths = STATS.breaths;
         if let Some(lastloadreport) = LASTLOADREPORT {
             let interval = now().duration_since(lastloadreport).as_secs_f64();
             let newfrees = frees - REPORTEDFREES;
@@ -330,9 +529,8 @@ pub fn report_links(state: &EngineState) {
     let mut names: Vec<_> = state.link_table.keys().collect();
     names.sort();
     for name in names {
-        let link = state.link_table.get(name).unwrap().borrow();
-        let txpackets = link.txpackets;
-        let txdrop = link.txdrop;
+        let shared_link = state.link_table.get(name).unwrap();
+        let (txpackets, _, txdrop) = link::tx_stats(shared_link);
         println!("{} sent on {} (loss rate: {}%)",
                  txpackets, name, loss_rate(txdrop, txpackets));
     }
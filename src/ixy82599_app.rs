@@ -27,17 +27,15 @@ impl engine::App for Ixy82599App {
     fn has_pull(&self) -> bool { true }
     fn pull(&self, app: &engine::AppState) {
         if let Some(output) = app.output.get("output") {
-            let mut output = output.borrow_mut();
             let mut ixy = self.ixy.borrow_mut();
-            ixy.rx_batch(0, &mut output, engine::PULL_NPACKETS);
+            ixy.rx_batch(0, output, engine::PULL_NPACKETS);
         }
     }
     fn has_push(&self) -> bool { true }
     fn push(&self, app: &engine::AppState) {
         if let Some(input) = app.input.get("input") {
-            let mut input = input.borrow_mut();
             let mut ixy = self.ixy.borrow_mut();
-            ixy.tx_batch(0, &mut input);
+            ixy.tx_batch(0, input);
         }
     }
     fn has_report(&self) -> bool { true }
@@ -128,9 +126,8 @@ mod selftest {
         fn has_pull(&self) -> bool { true }
         fn pull(&self, app: &engine::AppState) {
             if let Some(output) = app.output.get("output") {
-                let mut output = output.borrow_mut();
-                while !link::full(&output) {
-                    link::transmit(&mut output, packet::clone(&self.packet));
+                while !link::full(output) {
+                    link::transmit(output, packet::clone(&self.packet));
                 }
             }
         }
@@ -1,75 +1,129 @@
 // LINK STRUCT AND OPERATIONS
 //
 // This module defines a struct to represent unidirectional network links,
-// implemented as circular ring buffers, and link operations.
+// implemented as circular ring buffers, and link operations. A Link has
+// exactly one producer (transmit) and one consumer (receive); they may run
+// on different worker threads (see engine::run_workers' worker partitioning),
+// so the ring's cursors and counters are atomics rather than plain fields —
+// the same design as a bounded SPSC channel. Nothing about a Link lets more
+// than one producer or more than one consumer use it safely.
 //
 //   Link - opaque link structure
 //   LINK_MAX_PACKETS - capacity of a Link
 //   new() -> Link - allocate a new empty Link
 //   full(&Link) -> bool - predicate to test if Link is full
 //   empty(&Link) -> bool - predicate to test if Link is empty
-//   receive(&mut Link) -> Box<Packet> - dequeue a packet from the Link
-//   transmit(&mut Link, Box<Packet>) - enqueue a packet on the Link
+//   receive(&Link) -> Box<Packet> - dequeue a packet from the Link
+//   transmit(&Link, Box<Packet>) - enqueue a packet on the Link
+//   tx_stats(&Link) -> (txpackets, txbytes, txdrop)
+//   rx_stats(&Link) -> (rxpackets, rxbytes)
 
 use super::packet;
 
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+
 // Size of the ring buffer.
 const LINK_RING_SIZE: usize = 1024;
 
 // Capacity of a Link.
 pub const LINK_MAX_PACKETS: usize = LINK_RING_SIZE - 1;
 
+// Pads an atomic cursor out to its own cacheline, so the producer spinning
+// on 'write' and the consumer spinning on 'read' never false-share one.
+#[repr(align(64))]
+struct Aligned<T>(T);
+
 pub struct Link {
     // this is a circular ring buffer, as described at:
     //   http://en.wikipedia.org/wiki/Circular_buffer
-    packets: [*mut packet::Packet; LINK_RING_SIZE],
+    // UnsafeCell because transmit/receive only need a shared &Link (so a
+    // Link can be handed to a producer thread and a consumer thread at the
+    // same time); soundness relies on transmit only ever touching the slot
+    // at 'write' and receive only ever touching the slot at 'read', which
+    // the Acquire/Release handoff on the cursors below guarantees never
+    // overlap.
+    packets: UnsafeCell<[*mut packet::Packet; LINK_RING_SIZE]>,
     // Two cursors:
-    //   read:  the next element to be read
-    //   write: the next element to be written
-    read: i32, write: i32,
-    // Link stats:
-    pub txpackets: u64, pub txbytes: u64, pub txdrop: u64,
-    pub rxpackets: u64, pub rxbytes: u64
+    //   read:  the next element to be read (written only by receive)
+    //   write: the next element to be written (written only by transmit)
+    read: Aligned<AtomicI32>, write: Aligned<AtomicI32>,
+    // Link stats. Each is written by exactly one side (transmit or
+    // receive); they are atomics purely so that a reader on another thread
+    // (report_links, management, telemetry) never observes a torn value.
+    txpackets: AtomicU64, txbytes: AtomicU64, txdrop: AtomicU64,
+    rxpackets: AtomicU64, rxbytes: AtomicU64
 }
 
+// SAFETY: a Link is only ever sound when used as a single-producer/
+// single-consumer channel — see the module doc comment. Under that
+// discipline the raw pointers in 'packets' are never aliased across
+// threads, which is what Send/Sync would otherwise not be able to verify.
+unsafe impl Send for Link {}
+unsafe impl Sync for Link {}
+
 const SIZE: i32 = LINK_RING_SIZE as i32; // shorthand
 
 pub fn new() -> Link {
-    Link { packets: [std::ptr::null_mut(); LINK_RING_SIZE],
-           read: 0, write: 0,
-           txpackets: 0, txbytes: 0, txdrop: 0,
-           rxpackets: 0, rxbytes: 0 }
+    Link { packets: UnsafeCell::new([std::ptr::null_mut(); LINK_RING_SIZE]),
+           read: Aligned(AtomicI32::new(0)), write: Aligned(AtomicI32::new(0)),
+           txpackets: AtomicU64::new(0), txbytes: AtomicU64::new(0), txdrop: AtomicU64::new(0),
+           rxpackets: AtomicU64::new(0), rxbytes: AtomicU64::new(0) }
 }
 
-pub fn empty(r: &Link) -> bool { r.read == r.write }
+pub fn empty(r: &Link) -> bool {
+    r.read.0.load(Ordering::Acquire) == r.write.0.load(Ordering::Acquire)
+}
 
-pub fn full(r: &Link) -> bool { (r.write + 1) & (SIZE - 1) == r.read }
+pub fn full(r: &Link) -> bool {
+    let write = r.write.0.load(Ordering::Relaxed);
+    let read = r.read.0.load(Ordering::Acquire);
+    (write + 1) & (SIZE - 1) == read
+}
 
 // NB: non-empty assertion commented out in original Snabb, but since we get a
 // bunch of nice safety invariants from the Rust compiler, letâ€™s maintain them.
 // Box::from_raw will never alias because receive/transmit ensure any Packet is
 // either on a single Link, or on no Link at all.
-pub fn receive(r: &mut Link) -> Box<packet::Packet> {
+//
+// Must only be called by the single consumer of 'r'.
+pub fn receive(r: &Link) -> Box<packet::Packet> {
     if empty(r) { panic!("Link underflow."); }
-    let p = unsafe { Box::from_raw(r.packets[r.read as usize]) };
-    r.read = (r.read + 1) & (SIZE - 1);
-    r.rxpackets += 1;
-    r.rxbytes += p.length as u64;
+    let read = r.read.0.load(Ordering::Relaxed);
+    let p = unsafe { Box::from_raw((*r.packets.get())[read as usize]) };
+    r.read.0.store((read + 1) & (SIZE - 1), Ordering::Release);
+    r.rxpackets.fetch_add(1, Ordering::Relaxed);
+    r.rxbytes.fetch_add(p.length as u64, Ordering::Relaxed);
     p
 }
 
-pub fn transmit(r: &mut Link, mut p: Box<packet::Packet>) {
+// Must only be called by the single producer of 'r'.
+pub fn transmit(r: &Link, mut p: Box<packet::Packet>) {
     if full(r) {
-        r.txdrop += 1;
+        r.txdrop.fetch_add(1, Ordering::Relaxed);
         packet::free(p);
     } else {
-        r.txpackets += 1;
-        r.txbytes += p.length as u64;
-        r.packets[r.write as usize] = &mut *p; std::mem::forget(p);
-        r.write = (r.write + 1) & (SIZE - 1);
+        r.txpackets.fetch_add(1, Ordering::Relaxed);
+        r.txbytes.fetch_add(p.length as u64, Ordering::Relaxed);
+        let write = r.write.0.load(Ordering::Relaxed);
+        unsafe { (*r.packets.get())[write as usize] = &mut *p; }
+        std::mem::forget(p);
+        r.write.0.store((write + 1) & (SIZE - 1), Ordering::Release);
     }
 }
 
+// Snapshot of the producer-side counters.
+pub fn tx_stats(r: &Link) -> (u64, u64, u64) {
+    (r.txpackets.load(Ordering::Relaxed),
+     r.txbytes.load(Ordering::Relaxed),
+     r.txdrop.load(Ordering::Relaxed))
+}
+
+// Snapshot of the consumer-side counters.
+pub fn rx_stats(r: &Link) -> (u64, u64) {
+    (r.rxpackets.load(Ordering::Relaxed), r.rxbytes.load(Ordering::Relaxed))
+}
+
 // Ensure that Dropped Links are empty (otherwise Dropping a link would leak
 // its remaining enqueued packets).
 // NB: a non-empty Link going out of scope will trigger a panic.
@@ -2,8 +2,15 @@ mod packet;
 mod link;
 mod engine;
 mod config;
+mod management;
 mod lib;
 mod basic_apps;
+mod traffic;
+mod telemetry;
+mod device;
+mod tap;
+mod af_packet;
+mod af_xdp;
 
 use std::time::{Duration,Instant};
 
@@ -39,7 +46,7 @@ fn allocate() {
 }
 
 fn link() {
-    let mut r = link::new();
+    let r = link::new();
     println!("Allocated a link of capacity {}", link::LINK_MAX_PACKETS);
     let to_transmit = 2000;
     if link::full(&r) { panic!("Link should be empty."); }
@@ -47,58 +54,63 @@ fn link() {
         let mut p = packet::allocate();
         p.length = n;
         p.data[(n-1) as usize] = 42;
-        // Why is &, &mut not automatically inferred?
-        link::transmit(&mut r, p);
+        link::transmit(&r, p);
         //p.data[0] = 13 // Would cause compiler error.
-        //link::transmit(&mut r, p); // Would cause compile error
+        //link::transmit(&r, p); // Would cause compile error
     }
     println!("Transmitted {} packets", to_transmit);
     if link::empty(&r) || !link::full(&r) { panic!("Link should be full."); }
     let mut n = 0;
     while !link::empty(&r) {
         n += 1;
-        let p = link::receive(&mut r);
+        let p = link::receive(&r);
         if p.length != n as u16 || p.data[n-1] != 42 { panic!("Corrupt packet!"); }
         packet::free(p);
     }
-    //link::receive(&mut r); // Would cause link underflow panic.
+    //link::receive(&r); // Would cause link underflow panic.
     println!("Received {} packets", n);
+    let (rxpackets, rxbytes) = link::rx_stats(&r);
+    let (txpackets, txbytes, txdrop) = link::tx_stats(&r);
     println!("link: rxpackets={} rxbytes={} txpackets={} txbytes={} txdrop={}",
-             r.rxpackets, r.rxbytes, r.txpackets, r.txbytes, r.txdrop);
+             rxpackets, rxbytes, txpackets, txbytes, txdrop);
     // Failing to drain the link would cause panic
 }
 
 fn config () {
     let mut c = config::new();
     println!("Created an empty configuration");
-    config::app(&mut c, "source", &basic_apps::Source {size: 60});
+    config::app(&mut c, "source", &basic_apps::Source {size: 60}).unwrap();
     println!("Added an app");
-    config::link(&mut c, "source.output -> sink.input");
-    println!("Added an link");
+    // "sink" was never added, so this demonstrates the structured error path
+    // rather than panicking.
+    match config::link(&mut c, "source.output -> sink.input") {
+        Ok(()) => println!("Added an link"),
+        Err(e) => println!("Rejected link: {}", e)
+    }
 }
 
 fn engine(s: &mut engine::EngineState) {
     let mut c = config::new();
-    config::app(&mut c, "source", &basic_apps::Source {size: 60});
-    config::app(&mut c, "sink", &basic_apps::Sink {});
-    config::link(&mut c, "source.output -> sink.input");
-    engine::configure(s, &c);
+    config::app(&mut c, "source", &basic_apps::Source {size: 60}).unwrap();
+    config::app(&mut c, "sink", &basic_apps::Sink {}).unwrap();
+    config::link(&mut c, "source.output -> sink.input").unwrap();
+    engine::configure(s, &c).unwrap();
     println!("Configured the app network: source(60).output -> sink.input");
-    engine::main(&s, Some(engine::Options{
+    engine::main(s, Some(engine::Options{
         duration: Some(Duration::new(0,0)),
         report_load: true, report_links: true,
         ..Default::default()
-    }));
+    }), None);
     let mut c = c.clone();
-    config::app(&mut c, "source", &basic_apps::Source {size: 120});
-    engine::configure(s, &c);
+    config::app(&mut c, "source", &basic_apps::Source {size: 120}).unwrap();
+    engine::configure(s, &c).unwrap();
     println!("Cloned, mutated, and applied new configuration:");
     println!("source(120).output -> sink.input");
-    engine::main(&s, Some(engine::Options{
+    engine::main(s, Some(engine::Options{
         done: Some(Box::new(|_, _| true)),
         report_load: true, report_links: true,
         ..Default::default()
-    }));
+    }), None);
     let stats = engine::stats();
     println!("engine: frees={} freebytes={} freebits={}",
              stats.frees, stats.freebytes, stats.freebits);
@@ -107,69 +119,75 @@ fn engine(s: &mut engine::EngineState) {
 fn breathe_order(s: &mut engine::EngineState) {
     println!("Case 1:");
     let mut c = config::new();
-    config::app(&mut c, "a_io1", &basic_apps::SourceSink {size: 60});
-    config::app(&mut c, "b_t1", &basic_apps::Tee {});
-    config::app(&mut c, "c_t2", &basic_apps::Tee {});
-    config::app(&mut c, "d_t3", &basic_apps::Tee {});
-    config::link(&mut c, "a_io1.output -> b_t1.input");
-    config::link(&mut c, "b_t1.output -> c_t2.input");
-    config::link(&mut c, "b_t1.output2 -> d_t3.input");
-    config::link(&mut c, "d_t3.output -> b_t1.input2");
-    engine::configure(s, &c);
-    engine::report_links(s);
-    for name in &s.inhale { println!("pull {}", &name); }
-    for name in &s.exhale { println!("push {}", &name); }
+    config::app(&mut c, "a_io1", &basic_apps::SourceSink {size: 60}).unwrap();
+    config::app(&mut c, "b_t1", &basic_apps::Tee {}).unwrap();
+    config::app(&mut c, "c_t2", &basic_apps::Tee {}).unwrap();
+    config::app(&mut c, "d_t3", &basic_apps::Tee {}).unwrap();
+    config::link(&mut c, "a_io1.output -> b_t1.input").unwrap();
+    config::link(&mut c, "b_t1.output -> c_t2.input").unwrap();
+    config::link(&mut c, "b_t1.output2 -> d_t3.input").unwrap();
+    config::link(&mut c, "d_t3.output -> b_t1.input2").unwrap();
+    report_breathe_order(s, &c);
     println!("Case 2:");
     let mut c = config::new();
-    config::app(&mut c, "a_io1", &basic_apps::SourceSink {size: 60});
-    config::app(&mut c, "b_t1", &basic_apps::Tee {});
-    config::app(&mut c, "c_t2", &basic_apps::Tee {});
-    config::app(&mut c, "d_t3", &basic_apps::Tee {});
-    config::link(&mut c, "a_io1.output -> b_t1.input");
-    config::link(&mut c, "b_t1.output -> c_t2.input");
-    config::link(&mut c, "b_t1.output2 -> d_t3.input");
-    config::link(&mut c, "c_t2.output -> d_t3.input2");
-    engine::configure(s, &c);
-    engine::report_links(s);
-    for name in &s.inhale { println!("pull {}", &name); }
-    for name in &s.exhale { println!("push {}", &name); }
+    config::app(&mut c, "a_io1", &basic_apps::SourceSink {size: 60}).unwrap();
+    config::app(&mut c, "b_t1", &basic_apps::Tee {}).unwrap();
+    config::app(&mut c, "c_t2", &basic_apps::Tee {}).unwrap();
+    config::app(&mut c, "d_t3", &basic_apps::Tee {}).unwrap();
+    config::link(&mut c, "a_io1.output -> b_t1.input").unwrap();
+    config::link(&mut c, "b_t1.output -> c_t2.input").unwrap();
+    config::link(&mut c, "b_t1.output2 -> d_t3.input").unwrap();
+    config::link(&mut c, "c_t2.output -> d_t3.input2").unwrap();
+    report_breathe_order(s, &c);
     println!("Case 3:");
     let mut c = config::new();
-    config::app(&mut c, "a_io1", &basic_apps::SourceSink {size: 60});
-    config::app(&mut c, "b_t1", &basic_apps::Tee {});
-    config::app(&mut c, "c_t2", &basic_apps::Tee {});
-    config::link(&mut c, "a_io1.output -> b_t1.input");
-    config::link(&mut c, "a_io1.output2 -> c_t2.input");
-    config::link(&mut c, "b_t1.output -> a_io1.input");
-    config::link(&mut c, "b_t1.output2 -> c_t2.input2");
-    config::link(&mut c, "c_t2.output -> a_io1.input2");
-    engine::configure(s, &c);
-    engine::report_links(s);
-    for name in &s.inhale { println!("pull {}", &name); }
-    for name in &s.exhale { println!("push {}", &name); }
+    config::app(&mut c, "a_io1", &basic_apps::SourceSink {size: 60}).unwrap();
+    config::app(&mut c, "b_t1", &basic_apps::Tee {}).unwrap();
+    config::app(&mut c, "c_t2", &basic_apps::Tee {}).unwrap();
+    config::link(&mut c, "a_io1.output -> b_t1.input").unwrap();
+    config::link(&mut c, "a_io1.output2 -> c_t2.input").unwrap();
+    config::link(&mut c, "b_t1.output -> a_io1.input").unwrap();
+    config::link(&mut c, "b_t1.output2 -> c_t2.input2").unwrap();
+    config::link(&mut c, "c_t2.output -> a_io1.input2").unwrap();
+    report_breathe_order(s, &c);
+}
+
+// Apply 'c' and report the inhale/exhale order engine::configure computed
+// for it, or that the ordering couldn't be computed (e.g. a link cycle) -
+// this is the case config::ConfigError::Cycle exists for, so unlike the
+// rest of this file we don't just .unwrap() the result.
+fn report_breathe_order(s: &mut engine::EngineState, c: &config::Config) {
+    match engine::configure(s, c) {
+        Ok(()) => {
+            engine::report_links(s);
+            for name in &s.inhale { println!("pull {}", &name); }
+            for name in &s.exhale { println!("push {}", &name); }
+        }
+        Err(e) => println!("cannot compute breathe order: {}", e)
+    }
 }
 
 fn basic1 (s: &mut engine::EngineState, npackets: u64) {
     let mut c = config::new();
-    config::app(&mut c, "Source", &basic_apps::Source {size: 60});
-    config::app(&mut c, "Tee", &basic_apps::Tee {});
-    config::app(&mut c, "Sink", &basic_apps::Sink {});
-    config::link(&mut c, "Source.tx -> Tee.rx");
-    config::link(&mut c, "Tee.tx1 -> Sink.rx1");
-    config::link(&mut c, "Tee.tx2 -> Sink.rx2");
-    engine::configure(s, &c);
+    config::app(&mut c, "Source", &basic_apps::Source {size: 60}).unwrap();
+    config::app(&mut c, "Tee", &basic_apps::Tee {}).unwrap();
+    config::app(&mut c, "Sink", &basic_apps::Sink {}).unwrap();
+    config::link(&mut c, "Source.tx -> Tee.rx").unwrap();
+    config::link(&mut c, "Tee.tx1 -> Sink.rx1").unwrap();
+    config::link(&mut c, "Tee.tx2 -> Sink.rx2").unwrap();
+    engine::configure(s, &c).unwrap();
     let start = Instant::now();
-    let output = s.app_table.get("Source").unwrap().output.get("tx").unwrap();
-    while output.borrow().txpackets < npackets {
-        engine::main(&s, Some(engine::Options{
+    let output = s.app_table.get("Source").unwrap().output.get("tx").unwrap().clone();
+    while link::tx_stats(&output).0 < npackets {
+        engine::main(s, Some(engine::Options{
             duration: Some(Duration::new(0, 10_000_000)), // 0.01s
             no_report: true,
             ..Default::default()
-        }));
+        }), None);
     }
     let finish = Instant::now();
     let runtime = finish.duration_since(start).as_secs_f64();
-    let packets = output.borrow().txpackets as f64;
+    let packets = link::tx_stats(&output).0 as f64;
     println!("Processed {:.1} million packets in {:.2} seconds (rate: {:.1} Mpps).",
              packets / 1e6, runtime, packets / runtime / 1e6);
 }
@@ -0,0 +1,164 @@
+use super::engine;
+use super::config;
+use super::link;
+
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::net::TcpStream;
+
+// REMOTE MANAGEMENT/TELEMETRY SOCKET
+//
+// Lets a running engine be inspected, and reconfigured, from outside the
+// process. Server::bind() opens a non-blocking listening socket;
+// Server::poll(), called once per breath from engine::main, accepts pending
+// connections and answers any request that has already arrived in full —
+// packet processing is never held up waiting on a peer.
+//
+// Each request and response is UTF-8 text framed by a 4-byte big-endian
+// length prefix:
+//
+//   links    -> one line per link: "name rxpackets=.. rxbytes=.. txpackets=.. txbytes=.. txdrop=.."
+//   stats    -> "breaths=.. frees=.. freebits=.. freebytes=.."
+//   schedule -> "pull: a,b,..\npush: a,b,.." (the inhale/exhale order of the last configure())
+//   reload   -> apply the config::Config staged via Server::stage(); replies
+//               "ok" or "error: <ConfigError>"
+//
+// config::Config can't itself be shipped over the wire — it borrows its
+// apps' AppConfig values (see config.rs) — so a "reload" request only
+// triggers applying whatever Config the embedding program has staged ahead
+// of time via the same clone-mutate-reconfigure flow engine()'s demo in
+// main.rs uses locally.
+//
+//   Server - listening management socket, polled from engine::main
+//   Server::bind(addr) -> io::Result<Server> - start listening
+//   Server::stage(&Config) - set the config the next "reload" applies
+//   Server::poll(&mut EngineState) - service pending connections, non-blocking
+
+pub struct Server<'state> {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    next_config: Option<&'state config::Config<'state>>
+}
+
+impl<'state> Server<'state> {
+    // Start listening on 'addr' (e.g. "127.0.0.1:5000"). The listener and
+    // every accepted connection are non-blocking, so poll() can never stall
+    // the breathe loop on socket I/O.
+    pub fn bind(addr: &str) -> io::Result<Server<'state>> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Server { listener, clients: Vec::new(), next_config: None })
+    }
+
+    // Stage the config the next "reload" request should apply.
+    pub fn stage(&mut self, config: &'state config::Config<'state>) {
+        self.next_config = Some(config);
+    }
+
+    // Accept any pending connections and service any request that has
+    // already arrived in full, including applying a staged "reload".
+    // Called once per breath from engine::main; never blocks.
+    pub fn poll(&mut self, state: &mut engine::EngineState<'state>) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client { stream, buf: Vec::new() });
+            }
+        }
+        let mut i = 0;
+        while i < self.clients.len() {
+            if service(&mut self.clients[i], state, self.next_config) {
+                i += 1;
+            } else {
+                self.clients.remove(i);
+            }
+        }
+    }
+}
+
+// A management connection that may have a partially-received request.
+struct Client { stream: TcpStream, buf: Vec<u8> }
+
+// Service one client: answer a request if it has arrived in full. Returns
+// false if the connection should be dropped (closed, or errored).
+fn service(client: &mut Client, state: &mut engine::EngineState<'_>,
+           next_config: Option<&config::Config<'_>>) -> bool {
+    match try_read_frame(&mut client.stream, &mut client.buf) {
+        Ok(Some(request)) => {
+            let request = String::from_utf8_lossy(&request);
+            let response = handle(request.trim(), state, next_config);
+            send_frame(&mut client.stream, response.as_bytes()).is_ok()
+        }
+        Ok(None) => true, // request still incomplete; keep waiting
+        Err(_) => false   // closed or errored; drop the client
+    }
+}
+
+fn handle(request: &str, state: &mut engine::EngineState<'_>,
+          next_config: Option<&config::Config<'_>>) -> String {
+    match request {
+        "links" => dump_links(&*state),
+        "stats" => dump_stats(),
+        "schedule" => dump_schedule(&*state),
+        "reload" => match next_config {
+            Some(config) => match engine::configure(state, config) {
+                Ok(()) => "ok".to_string(),
+                Err(e) => format!("error: {}", e)
+            },
+            None => "error: no config staged (see management::Server::stage)".to_string()
+        },
+        other => format!("error: unknown request {:?}", other)
+    }
+}
+
+fn dump_links(state: &engine::EngineState<'_>) -> String {
+    let mut names: Vec<_> = state.link_table.keys().collect();
+    names.sort();
+    names.iter().map(|name| {
+        let shared_link = state.link_table.get(*name).unwrap();
+        let (rxpackets, rxbytes) = link::rx_stats(shared_link);
+        let (txpackets, txbytes, txdrop) = link::tx_stats(shared_link);
+        format!("{} rxpackets={} rxbytes={} txpackets={} txbytes={} txdrop={}",
+                name, rxpackets, rxbytes, txpackets, txbytes, txdrop)
+    }).collect::<Vec<_>>().join("\n")
+}
+
+fn dump_stats() -> String {
+    let stats = engine::stats();
+    format!("breaths={} frees={} freebits={} freebytes={}",
+            stats.breaths, stats.frees, stats.freebits, stats.freebytes)
+}
+
+fn dump_schedule(state: &engine::EngineState<'_>) -> String {
+    // The inhale/exhale order computed by the last successful
+    // engine::configure() call (see its doc comment).
+    let pull = state.inhale.join(",");
+    let push = state.exhale.join(",");
+    format!("pull: {}\npush: {}", pull, push)
+}
+
+// Read whatever is available from 'stream' into 'buf' without blocking, and
+// return the payload of a complete length-prefixed frame once one has fully
+// arrived. Returns Ok(None) while a frame is still incomplete, and Err if
+// the peer closed the connection or a read failed.
+fn try_read_frame(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed")),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => return Err(e)
+        }
+    }
+    if buf.len() < 4 { return Ok(None); }
+    let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    if buf.len() < 4 + len { return Ok(None); }
+    let frame = buf[4..4 + len].to_vec();
+    buf.drain(0..4 + len);
+    Ok(Some(frame))
+}
+
+fn send_frame(stream: &mut TcpStream, payload: &[u8]) -> io::Result<()> {
+    stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+    stream.write_all(payload)
+}
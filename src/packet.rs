@@ -1,11 +1,20 @@
 use super::engine;
 
 use std::cmp::max;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 // PACKET STRUCT AND FREELIST
 //
 // This module defines a struct to represent packets of network data, and
-// implements a global freelist from which packets can be allocated.
+// implements a global freelist from which packets can be allocated. Packets
+// can cross from one worker thread to another via a link (see link.rs), so
+// the freelist itself must be safe to allocate from and free to concurrently
+// rather than assuming a single engine thread. It is a Treiber-style stack:
+// each free packet is linked to the next via its own 'next' field, and the
+// stack head is guarded by a Mutex (see FL_HEAD below) so concurrent
+// allocate()/free() calls serialize instead of racing.
 //
 //   Packet - packet structure with length and data fields
 //   PAYLOAD_SIZE - size of packet’s data field
@@ -21,9 +30,35 @@ pub const PAYLOAD_SIZE: usize = 1024*10;
 // access members. Is the memory layout in repr(rust) equivalent?
 pub struct Packet {
     pub length: u16, // data payload length
+    // RSS hash computed by the NIC for this packet's flow (see
+    // ixy82599::ixgbe's set_rss()), or 0 for packets that didn't arrive via
+    // an RSS-enabled receive queue. Lets downstream apps steer by flow
+    // without recomputing a hash themselves.
+    pub rss_hash: u32,
+    // Hardware TX offloads requested for this packet (see the TXOFFLOAD_*
+    // bits below) and the header lengths/MSS the NIC needs to perform them
+    // (see ixy82599::ixgbe's tx_batch). Ignored unless 'offload' is nonzero.
+    pub offload: u8,
+    pub l2_len: u16,
+    pub l3_len: u16,
+    pub l4_len: u16,
+    pub mss: u16,
+    // Intrusive link to the next packet on the freelist (see FL_HEAD below);
+    // null while the packet is allocated/in use. Lives alongside the other
+    // metadata fields rather than inside 'data' because, unlike
+    // ixy82599::ixgbe's Pool, FL is not trying to share storage with a
+    // hardware descriptor ring.
+    next: AtomicPtr<Packet>,
     pub data: [u8; PAYLOAD_SIZE]
 }
 
+// Bits for Packet::offload, requesting hardware TX checksum/segmentation
+// offloads from a NIC driver that supports them (see ixy82599::ixgbe).
+pub const TXOFFLOAD_IPV4: u8 = 0x01; // compute the IPv4 header checksum
+pub const TXOFFLOAD_TCP:  u8 = 0x02; // compute the TCP checksum
+pub const TXOFFLOAD_UDP:  u8 = 0x04; // compute the UDP checksum
+pub const TXOFFLOAD_TSO:  u8 = 0x08; // segment payload into 'mss'-sized segments
+
 // A packet may never go out of scope. It is either on the freelist, a link, or
 // in active use (in-scope).
 // XXX - Could free() packets automatically in Drop, and obsolete manual free.
@@ -34,50 +69,71 @@ impl Drop for Packet { fn drop(&mut self) { panic!("Packet leaked"); } }
 // XXX - This is a stub. Eventually packets may need to be allocated in DMA
 // pages, and follow strict alignment invariants.
 fn new_packet() -> Box<Packet> {
-    Box::new(Packet { length: 0, data: [0; PAYLOAD_SIZE] })
+    Box::new(Packet {
+        length: 0, rss_hash: 0,
+        offload: 0, l2_len: 0, l3_len: 0, l4_len: 0, mss: 0,
+        next: AtomicPtr::new(ptr::null_mut()),
+        data: [0; PAYLOAD_SIZE]
+    })
 }
 
 // Number of packets initially on the freelist.
 const FREELIST_SIZE: usize = 100_000;
 
-// Freelist consists of an array of mutable raw pointers to Packet,
-// and a fill counter.
-struct Freelist {
-    list: [*mut Packet; FREELIST_SIZE],
-    nfree: usize
-}
+// FL_HEAD is the top of the freelist: a *mut Packet (null when the
+// freelist is empty, as it is here initially), stored as a usize so the
+// static stays Sync without an unsafe impl.
+//
+// This used to be a tagged pointer - a generation counter packed into the
+// unused top 16 bits of the 48-bit pointer, CAS'd as one AtomicU64 so
+// concurrent allocate()/free() calls never blocked one another - but FL is
+// the global freelist every packet in the engine cycles through, churned
+// far harder than a single device's buffer pool, and a 16-bit counter
+// wraps within 65536 pops of the same stack slot: exactly the ABA window
+// ixy82599::ixgbe's Pool hit under the same scheme (see its fix). Stable
+// Rust has no AtomicU128/cmpxchg16b to widen the CAS to two words instead,
+// so the head is Mutex-guarded here: push/pop serialize, which costs the
+// lock-free property under contention, but there's no compare-exchange
+// left to race a stale read with.
+static FL_HEAD: Mutex<usize> = Mutex::new(0);
+// Number of packets currently on the freelist, tracked alongside FL_HEAD so
+// allocate()/free_internal() can still enforce the under/overflow panics
+// below without walking the stack.
+static FL_NFREE: AtomicUsize = AtomicUsize::new(0);
 
-// FL: global freelist (initially empty, populated with null ptrs).
-static mut FL: Freelist = Freelist {
-    list: [std::ptr::null_mut(); FREELIST_SIZE],
-    nfree: 0
-};
+// Push 'p' onto the freelist's stack.
+fn fl_push(p: *mut Packet) {
+    let mut head = FL_HEAD.lock().unwrap();
+    let top = *head as *mut Packet;
+    unsafe { (*p).next.store(top, Ordering::Relaxed); }
+    *head = p as usize;
+}
 
 // Fill up FL with freshly allocated packets.
-// NB: using FL is unsafe because it is a mutable static (we have to ensure
-// thread safety).
-// NB: we can cast a mutable reference of the boxed packet (&mut *p) to a raw
-// pointer.
-// NB: we std::mem::forget the Box p before it exits scope to avoid the heap
-// allocated packet from being Dropped (i.e., we intentionally leak
-// FREELIST_SIZE packets onto the static FL).
+// NB: we intentionally leak FREELIST_SIZE packets onto the static FL by
+// pushing the raw pointer obtained from Box::into_raw, rather than letting
+// the Box go out of scope (which would Drop and panic, see Packet).
 // XXX - eventually, new memory needs to be allocated on-demand dynamically.
 pub fn init() {
-    while unsafe { FL.nfree < FREELIST_SIZE } {
-        let mut p = new_packet();
-        unsafe { FL.list[FL.nfree] = &mut *p; } std::mem::forget(p);
-        unsafe { FL.nfree += 1; }
+    while FL_NFREE.load(Ordering::Relaxed) < FREELIST_SIZE {
+        let p = Box::into_raw(new_packet());
+        fl_push(p);
+        FL_NFREE.fetch_add(1, Ordering::Relaxed);
     }
 }
 
 // Allocate an empty Boxed Packet from FL.
-// NB: we can use Box::from_raw safely on the packets "leaked" onto
-// the static FL. We can also be sure that the Box does not alias another
-// packet (see free).
+// NB: we can use Box::from_raw safely on the packets pushed onto FL: each
+// is only ever linked into the stack once (see fl_push/free_internal), so
+// popping it here can't alias another live Box of the same packet.
 pub fn allocate() -> Box<Packet> {
-    if unsafe { FL.nfree == 0 } { panic!("Packet freelist underflow"); }
-    unsafe { FL.nfree -= 1; }
-    unsafe { Box::from_raw(FL.list[FL.nfree]) }
+    let mut head = FL_HEAD.lock().unwrap();
+    let p = *head as *mut Packet;
+    if p.is_null() { panic!("Packet freelist underflow"); }
+    let next = unsafe { (*p).next.load(Ordering::Relaxed) };
+    *head = next as usize;
+    FL_NFREE.fetch_sub(1, Ordering::Relaxed);
+    unsafe { Box::from_raw(p) }
 }
 
 // Return Boxed Packet to FL.
@@ -85,15 +141,15 @@ pub fn allocate() -> Box<Packet> {
 // effectively consumes the Box. Once a packet is freed it can no longer be
 // referenced, and hence can not me mutated once it has been returned to the
 // freelist.
-// NB: we std::mem::forget the Box p to inhibit Dropping of the packet once it
-// is on the freelist. If a packet goes out of scope without being freed, the
-// attempt to Drop it will trigger a panic (see Packet). Hence we ensure that
-// all allocated packets are eventually freed.
+// NB: Box::into_raw inhibits Dropping of the packet once it is on the
+// freelist. If a packet goes out of scope without being freed, the attempt
+// to Drop it will trigger a panic (see Packet). Hence we ensure that all
+// allocated packets are eventually freed.
 fn free_internal(mut p: Box<Packet>) {
-    if unsafe { FL.nfree } == FREELIST_SIZE { panic!("Packet freelist overflow"); }
+    if FL_NFREE.load(Ordering::Relaxed) == FREELIST_SIZE { panic!("Packet freelist overflow"); }
     p.length = 0;
-    unsafe { FL.list[FL.nfree] = &mut *p; } std::mem::forget(p);
-    unsafe { FL.nfree += 1; }
+    fl_push(Box::into_raw(p));
+    FL_NFREE.fetch_add(1, Ordering::Relaxed);
 }
 pub fn free (p: Box<Packet>) {
     engine::add_frees();
@@ -0,0 +1,110 @@
+use super::packet;
+use super::engine;
+use super::device::{Device, SpscQueue, ChannelDevice, DeviceApp};
+
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::io::{Read, Write};
+
+// TAP DEVICE APP
+//
+// Moves packets between the engine and a Linux TAP network interface. The
+// TAP file descriptor is owned by a background thread (see device.rs); this
+// module only knows how to open the interface and feed bytes across it.
+//
+//   Tap - AppConfig: {ifname, mtu} opens/creates the named tap interface
+
+const IFNAMSIZ: usize = 16;
+const IFF_TAP: libc::c_short = 0x0002;
+const IFF_NO_PI: libc::c_short = 0x1000;
+const TUNSETIFF: libc::c_ulong = 0x4004_54ca;
+
+#[repr(C)]
+struct ifreq {
+    ifr_name: [libc::c_char; IFNAMSIZ],
+    ifr_flags: libc::c_short,
+    _padding: [u8; 22]
+}
+
+fn open_tap(ifname: &str) -> File {
+    let tun = OpenOptions::new().read(true).write(true)
+        .open("/dev/net/tun")
+        .expect("failed to open /dev/net/tun (need CAP_NET_ADMIN)");
+
+    let mut req: ifreq = unsafe { std::mem::zeroed() };
+    assert!(ifname.len() < IFNAMSIZ, "interface name too long");
+    for (dst, src) in req.ifr_name.iter_mut().zip(ifname.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    req.ifr_flags = IFF_TAP | IFF_NO_PI;
+
+    let res = unsafe { libc::ioctl(tun.as_raw_fd(), TUNSETIFF, &req) };
+    assert!(res >= 0, "TUNSETIFF failed for interface {}", ifname);
+
+    tun
+}
+
+// Background RX thread: blocks on reading frames off the tap fd and hands
+// them to the engine through the rx SpscQueue (see device::ChannelDevice).
+fn rx_thread(mut file: File, rx: Arc<SpscQueue<Box<packet::Packet>>>,
+             link_up: Arc<AtomicBool>) {
+    link_up.store(true, Ordering::Relaxed);
+    loop {
+        let mut p = packet::allocate();
+        match file.read(&mut p.data) {
+            Ok(n) if n > 0 => {
+                p.length = n as u16;
+                if let Err(p) = rx.try_push(p) {
+                    // Engine isn't keeping up; drop the frame.
+                    packet::free(p);
+                }
+            }
+            _ => packet::free(p)
+        }
+    }
+}
+
+// Background TX thread: blocks waiting for packets the engine wants to send
+// and writes them out to the tap fd.
+fn tx_thread(mut file: File, tx: Arc<SpscQueue<Box<packet::Packet>>>) {
+    loop {
+        let mut p = tx.pop_blocking();
+        let _ = file.write(&p.data[..p.length as usize]);
+        p.length = 0;
+        packet::free(p);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Tap { pub ifname: String, pub mtu: usize }
+
+impl engine::AppConfig for Tap {
+    fn new(&self) -> Box<dyn engine::App> {
+        let file = open_tap(&self.ifname);
+        let tx_file = file.try_clone().expect("failed to dup tap fd");
+
+        let rx: Arc<SpscQueue<Box<packet::Packet>>> = SpscQueue::new(1024);
+        let tx: Arc<SpscQueue<Box<packet::Packet>>> = SpscQueue::new(1024);
+        let link_up = Arc::new(AtomicBool::new(false));
+
+        let rx_queue = rx.clone();
+        let rx_link_up = link_up.clone();
+        thread::Builder::new()
+            .name(format!("tap-rx:{}", self.ifname))
+            .spawn(move || rx_thread(file, rx_queue, rx_link_up))
+            .expect("failed to spawn tap RX thread");
+
+        let tx_queue = tx.clone();
+        thread::Builder::new()
+            .name(format!("tap-tx:{}", self.ifname))
+            .spawn(move || tx_thread(tx_file, tx_queue))
+            .expect("failed to spawn tap TX thread");
+
+        let device: Box<dyn Device> =
+            Box::new(ChannelDevice::new(rx, tx, self.mtu, link_up));
+        Box::new(DeviceApp::new(device))
+    }
+}
@@ -0,0 +1,192 @@
+use super::packet;
+use super::link;
+use super::engine;
+use super::lib;
+
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+// SYNTHETIC TRAFFIC GENERATOR
+//
+// basic_apps::Source only ever emits fixed-size packets (Source {size: 60}).
+// This module adds Generator, a Source analogue whose packets come from a
+// pluggable Traffic model instead of a single constant size, so a config can
+// ask for a realistic load (size mixes, paced arrivals, bursts, many flows)
+// and compare the resulting engine::report_load() output against a plain
+// Source, the way basic1 benchmarks Source/Tee/Sink today.
+//
+//   Traffic - trait implemented by a packet-content model
+//   SizeDist - packet-size distribution (Uniform or Imix)
+//   Pacing - when a Generator's pull() is allowed to emit packets
+//   Generator - AppConfig selecting a SizeDist, Pacing, and flow count
+
+// Produces the packets a Generator emits. Implementations decide size and
+// content (e.g. cycling through a set of flow identifiers); Generator itself
+// only decides *when* pull() is allowed to call next_packet() (see Pacing).
+//
+// Send because a GeneratorApp (and the Box<dyn Traffic> inside it) may run
+// on any one of engine::run_workers()'s threads.
+pub trait Traffic: std::fmt::Debug + Send {
+    fn next_packet(&mut self) -> Box<packet::Packet>;
+}
+
+// Packet-size distribution.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SizeDist {
+    // Uniform random size in [min, max] bytes, inclusive.
+    Uniform { min: u16, max: u16 },
+    // Internet Mix: sizes drawn from 'sizes', weighted by the matching entry
+    // in 'weights' (e.g. the classic 7/4/1 64/594/1500-byte IMIX is
+    // sizes: vec![64, 594, 1500], weights: vec![7, 4, 1]).
+    Imix { sizes: Vec<u16>, weights: Vec<u32> }
+}
+
+impl SizeDist {
+    fn sample(&self, rng: &mut Rng) -> u16 {
+        match self {
+            SizeDist::Uniform { min, max } if min < max =>
+                min + (rng.next_u64() % (*max - *min + 1) as u64) as u16,
+            SizeDist::Uniform { min, .. } => *min,
+            SizeDist::Imix { sizes, weights } => {
+                let total: u64 = weights.iter().map(|&w| w as u64).sum();
+                let mut pick = rng.next_u64() % total.max(1);
+                for (&size, &weight) in sizes.iter().zip(weights) {
+                    if pick < weight as u64 { return size; }
+                    pick -= weight as u64;
+                }
+                *sizes.last().unwrap_or(&60)
+            }
+        }
+    }
+}
+
+// A Traffic model combining a SizeDist with per-flow 5-tuple rotation.
+// Each packet's first four bytes are stamped with its flow id (0..flows,
+// round-robin) so downstream apps or a packet capture can tell the flows
+// apart; this stands in for a real Ethernet/IP/UDP 5-tuple until header
+// synthesis for those protocols exists in this crate (c.f. header.rs).
+#[derive(Debug)]
+struct Model {
+    sizes: SizeDist,
+    flows: u32,
+    next_flow: u32,
+    rng: Rng
+}
+impl Traffic for Model {
+    fn next_packet(&mut self) -> Box<packet::Packet> {
+        // Clamp to Packet::data's capacity: an operator-configured SizeDist
+        // has no upper bound tied to it, but p.data does, and p.length must
+        // never claim more bytes than p.data actually holds (ixy82599's
+        // tx_batch DMAs p.data.as_ptr() for exactly p.length bytes).
+        let size = self.sizes.sample(&mut self.rng).max(4).min(packet::PAYLOAD_SIZE as u16);
+        let mut p = packet::allocate();
+        lib::fill(&mut p.data, size as usize, 0);
+        if self.flows > 0 {
+            p.data[0..4].copy_from_slice(&self.next_flow.to_be_bytes());
+            self.next_flow = (self.next_flow + 1) % self.flows;
+        }
+        p.length = size;
+        p
+    }
+}
+
+// When a Generator's pull() is allowed to emit packets.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pacing {
+    // Emit up to engine::PULL_NPACKETS packets every breath, like
+    // basic_apps::Source.
+    Continuous,
+    // Cap the long-run rate at 'pps', releasing packets one at a time at
+    // Poisson-distributed inter-arrival times (the standard memoryless
+    // arrival model for independent flows sharing a link).
+    Poisson { pps: f64 },
+    // Emit 'len' packets back-to-back, then idle for 'period' before the
+    // next burst (a burst train).
+    Burst { len: usize, period: Duration }
+}
+
+// Generator app: a basic_apps::Source analogue whose packets are produced by
+// a pluggable Traffic model (size: 'sizes', 5-tuple rotation: 'flows')
+// instead of a single fixed size, and paced by 'pacing' instead of emitting
+// unconditionally every breath.
+#[derive(Debug, Clone)]
+pub struct Generator { pub sizes: SizeDist, pub pacing: Pacing, pub flows: u32 }
+impl engine::AppConfig for Generator {
+    fn new(&self) -> Box<dyn engine::App> {
+        Box::new(GeneratorApp {
+            model: RefCell::new(Box::new(Model {
+                sizes: self.sizes.clone(),
+                flows: self.flows,
+                next_flow: 0,
+                rng: Rng::new(0x2545_F491_4F6C_DD1D ^ self.flows as u64)
+            })),
+            pacing: self.pacing.clone(),
+            pacing_rng: RefCell::new(Rng::new(!(0x2545_F491_4F6C_DD1D ^ self.flows as u64))),
+            next_arrival: RefCell::new(engine::now()),
+            burst_since: RefCell::new(engine::now())
+        })
+    }
+}
+pub struct GeneratorApp {
+    model: RefCell<Box<dyn Traffic>>,
+    pacing: Pacing,
+    pacing_rng: RefCell<Rng>,        // inter-arrival draws, under Pacing::Poisson
+    next_arrival: RefCell<Instant>, // next packet due, under Pacing::Poisson
+    burst_since: RefCell<Instant>   // start of current idle period, under Pacing::Burst
+}
+impl engine::App for GeneratorApp {
+    fn pull(&self, app: &engine::AppState) {
+        for output in app.output.values() {
+            let mut model = self.model.borrow_mut();
+            match &self.pacing {
+                Pacing::Continuous => {
+                    for _ in 0..engine::PULL_NPACKETS {
+                        link::transmit(output, model.next_packet());
+                    }
+                }
+                Pacing::Poisson { pps } => {
+                    let now = engine::now();
+                    let mut next = self.next_arrival.borrow_mut();
+                    let mut rng = self.pacing_rng.borrow_mut();
+                    for _ in 0..engine::PULL_NPACKETS {
+                        if *next > now { break; }
+                        link::transmit(output, model.next_packet());
+                        *next += exponential(&mut rng, *pps);
+                    }
+                }
+                Pacing::Burst { len, period } => {
+                    let now = engine::now();
+                    let mut since = self.burst_since.borrow_mut();
+                    if now.duration_since(*since) >= *period {
+                        for _ in 0..*len {
+                            link::transmit(output, model.next_packet());
+                        }
+                        *since = now;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Sample an Exponential(pps) distributed Duration: the inter-arrival time of
+// a Poisson process with mean rate 'pps' events per second.
+fn exponential(rng: &mut Rng, pps: f64) -> Duration {
+    let u = (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64; // [0, 1)
+    Duration::from_secs_f64(-u.max(f64::MIN_POSITIVE).ln() / pps)
+}
+
+// Minimal, fast, deterministic PRNG (xorshift64*), used to sample SizeDist
+// and Pacing::Poisson. Not cryptographic; good enough for traffic shaping
+// and keeps this crate free of an external rand dependency.
+#[derive(Debug, Clone)]
+struct Rng { state: u64 }
+impl Rng {
+    fn new(seed: u64) -> Rng { Rng { state: seed | 1 } }
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}